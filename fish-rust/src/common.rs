@@ -1,8 +1,15 @@
-use crate::ffi;
 use crate::wchar_ext::WExt;
-use crate::wchar_ffi::c_str;
-use crate::wchar_ffi::{wstr, WCharFromFFI, WString};
-use std::{ffi::c_uint, mem};
+use crate::wchar_ffi::{wstr, WString};
+use std::mem;
+
+// The old C++ escape_string implementation, kept around only so the native port below can be
+// differentially tested against it. Remove once the native port has proven itself.
+#[cfg(feature = "escape_string_ffi_diff")]
+use crate::ffi;
+#[cfg(feature = "escape_string_ffi_diff")]
+use crate::wchar_ffi::{c_str, WCharFromFFI};
+#[cfg(feature = "escape_string_ffi_diff")]
+use std::ffi::c_uint;
 
 /// A scoped manager to save the current value of some variable, and optionally set it to a new
 /// value. When dropped, it restores the variable to its old value.
@@ -37,17 +44,21 @@ impl<'a, T> Drop for ScopedPush<'a, T> {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum EscapeStringStyle {
-    Script(EscapeFlags),
+pub enum EscapeStringStyle<'a> {
+    Script(EscapeFlags<'a>),
     Url,
     Var,
     Regex,
+    /// ANSI-C / POSIX `$'...'` quoting, as understood by bash, zsh, and other POSIX-ish shells.
+    /// Unlike `Script`, the result isn't meant to be valid fish syntax - it's for producing
+    /// output other shells can consume unambiguously.
+    PosixDollar,
 }
 
 /// Flags for the [`escape_string()`] function. These are only applicable when the escape style is
 /// [`EscapeStringStyle::Script`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub struct EscapeFlags {
+pub struct EscapeFlags<'a> {
     /// Do not escape special fish syntax characters like the semicolon. Only escape non-printable
     /// characters and backslashes.
     pub no_printables: bool,
@@ -58,10 +69,40 @@ pub struct EscapeFlags {
     pub no_tilde: bool,
     /// Replace non-printable control characters with Unicode symbols.
     pub symbolic: bool,
+    /// Force these characters to be backslash-escaped even if they wouldn't otherwise need it -
+    /// for callers (e.g. completions, prompt rendering) that need a character escaped only in
+    /// their particular context.
+    pub additional_escaped_chars: &'a [char],
 }
 
 /// Replace special characters with backslash escape sequences. Newline is replaced with `\n`, etc.
-pub fn escape_string(s: &wstr, style: EscapeStringStyle) -> WString {
+pub fn escape_string(s: &wstr, style: EscapeStringStyle<'_>) -> WString {
+    let result = match style {
+        EscapeStringStyle::Script(flags) => escape_string_script(s, flags),
+        EscapeStringStyle::Url => escape_string_url(s),
+        EscapeStringStyle::Var => escape_string_var(s),
+        EscapeStringStyle::Regex => escape_string_regex(s),
+        EscapeStringStyle::PosixDollar => escape_string_posix_dollar(s),
+    };
+
+    // PosixDollar was added after this legacy shim; there's no C++ side to diff it against.
+    #[cfg(feature = "escape_string_ffi_diff")]
+    if !matches!(style, EscapeStringStyle::PosixDollar) {
+        let legacy = escape_string_ffi(s, style);
+        debug_assert_eq!(
+            result, legacy,
+            "native escape_string diverged from ffi::escape_string for style {:?}",
+            style
+        );
+    }
+
+    result
+}
+
+/// The pre-port implementation, which marshals flags into an int and calls the C++
+/// `escape_string`. Only compiled in when diffing the native port against it; not used otherwise.
+#[cfg(feature = "escape_string_ffi_diff")]
+fn escape_string_ffi(s: &wstr, style: EscapeStringStyle<'_>) -> WString {
     let mut flags_int = 0;
 
     let style = match style {
@@ -89,11 +130,1032 @@ pub fn escape_string(s: &wstr, style: EscapeStringStyle) -> WString {
         EscapeStringStyle::Url => ffi::escape_string_style_t::STRING_STYLE_URL,
         EscapeStringStyle::Var => ffi::escape_string_style_t::STRING_STYLE_VAR,
         EscapeStringStyle::Regex => ffi::escape_string_style_t::STRING_STYLE_REGEX,
+        EscapeStringStyle::PosixDollar => {
+            // Added after this legacy shim; there's no C++ side to diff against.
+            unreachable!("PosixDollar has no ffi equivalent")
+        }
     };
 
     ffi::escape_string(c_str!(s), flags_int.into(), style).from_ffi()
 }
 
+/// Escape `input` the way [`EscapeStringStyle::Script`] expects: backslash-escape control and
+/// shell-special characters, or fall back to wrapping the whole string in single quotes if that's
+/// shorter.
+fn escape_string_script(input: &wstr, flags: EscapeFlags<'_>) -> WString {
+    let EscapeFlags {
+        no_printables,
+        no_quoted,
+        no_tilde,
+        symbolic,
+        additional_escaped_chars,
+    } = flags;
+
+    if !no_quoted && input.is_empty() {
+        let mut empty_quotes = WString::new();
+        empty_quotes.push('\'');
+        empty_quotes.push('\'');
+        return empty_quotes;
+    }
+
+    let chars = input.as_char_slice();
+    let mut out = WString::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_control() {
+            if symbolic {
+                if let Some(picture) = control_picture(c) {
+                    out.push(picture);
+                    continue;
+                }
+            }
+            if let Some(letter) = named_control_escape(c) {
+                out.push('\\');
+                out.push(letter);
+            } else {
+                push_hex_escape(&mut out, c);
+            }
+            continue;
+        }
+
+        let mut needs_escape = c == '\\';
+        if !no_printables && is_script_special(c, i, no_tilde) {
+            needs_escape = true;
+        }
+        if !needs_escape && additional_escaped_chars.contains(&c) {
+            needs_escape = true;
+        }
+        if needs_escape {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+
+    // Single-quoting can't express a forced escape - single quotes take their contents
+    // completely literally, so there's no way to honor `additional_escaped_chars` inside them.
+    // For the same reason they'd leave control characters raw instead of showing the `symbolic`
+    // pictures.
+    if no_quoted
+        || !additional_escaped_chars.is_empty()
+        || (symbolic && chars.iter().any(|c| c.is_control()))
+    {
+        return out;
+    }
+
+    let quoted = single_quote_escape(chars);
+    if quoted.len() < out.len() {
+        quoted
+    } else {
+        out
+    }
+}
+
+/// The Unicode "Control Pictures" glyph standing in for a control character, used by
+/// [`EscapeFlags::symbolic`]. Returns `None` for control characters with no assigned picture.
+fn control_picture(c: char) -> Option<char> {
+    match c as u32 {
+        0x00..=0x1F => char::from_u32(0x2400 + c as u32),
+        0x7F => Some('\u{2421}'),
+        _ => None,
+    }
+}
+
+/// The single-letter suffix of a named backslash escape (`\n`, `\t`, ...) for `c`, or `None` if
+/// `c` has no short name and must fall back to a `\xHH`/`\uHHHH` escape.
+fn named_control_escape(c: char) -> Option<char> {
+    Some(match c {
+        '\n' => 'n',
+        '\t' => 't',
+        '\r' => 'r',
+        '\x07' => 'a', // BEL
+        '\x08' => 'b', // backspace
+        '\x1b' => 'e', // ESC
+        '\x0c' => 'f', // form feed
+        '\x0b' => 'v', // vertical tab
+        _ => return None,
+    })
+}
+
+/// Append a `\xHH`, `\uHHHH`, or `\UHHHHHHHH` escape for a non-printable character that has no
+/// short name, sized to the smallest form that fits the codepoint.
+fn push_hex_escape(out: &mut WString, c: char) {
+    let code = c as u32;
+    out.push('\\');
+    if code <= 0xFF {
+        out.push('x');
+        push_hex_digits(out, code, 2);
+    } else if code <= 0xFFFF {
+        out.push('u');
+        push_hex_digits(out, code, 4);
+    } else {
+        out.push('U');
+        push_hex_digits(out, code, 8);
+    }
+}
+
+/// Append exactly `width` lowercase hex digits of `value`, zero-padded.
+fn push_hex_digits(out: &mut WString, value: u32, width: usize) {
+    for shift in (0..width).rev() {
+        let nibble = (value >> (shift * 4)) & 0xF;
+        out.push(char::from_digit(nibble, 16).unwrap());
+    }
+}
+
+/// Whether `c` at position `index` is one of the characters fish's script tokenizer treats
+/// specially, and therefore needs a backslash to be taken literally.
+fn is_script_special(c: char, index: usize, no_tilde: bool) -> bool {
+    match c {
+        ';' | '|' | '&' | '$' | '(' | ')' | '<' | '>' | ' ' | '"' | '\'' | '{' | '}' | '[' | ']'
+        | '*' | '?' => true,
+        '~' => !no_tilde && index == 0,
+        '#' => index == 0,
+        _ => false,
+    }
+}
+
+/// Wrap `chars` in single quotes, escaping only the characters that are special inside single
+/// quotes (backslash and the quote itself).
+fn single_quote_escape(chars: &[char]) -> WString {
+    let mut out = WString::new();
+    out.push('\'');
+    for &c in chars {
+        if c == '\\' || c == '\'' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('\'');
+    out
+}
+
+/// Percent-encode `input` for use in a URL, leaving unreserved characters untouched.
+fn escape_string_url(input: &wstr) -> WString {
+    let mut out = WString::new();
+    let mut buf = [0u8; 4];
+    for c in input.as_char_slice().iter().copied() {
+        if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~') {
+            out.push(c);
+            continue;
+        }
+        for byte in c.encode_utf8(&mut buf).as_bytes() {
+            out.push('%');
+            out.push(char::from_digit((byte >> 4) as u32, 16).unwrap().to_ascii_uppercase());
+            out.push(char::from_digit((byte & 0xF) as u32, 16).unwrap().to_ascii_uppercase());
+        }
+    }
+    out
+}
+
+/// Encode `input` so it can be embedded as (part of) a fish variable name: alphanumerics pass
+/// through, a literal underscore doubles, and anything else becomes `_<hex>_`.
+fn escape_string_var(input: &wstr) -> WString {
+    let mut out = WString::new();
+    for c in input.as_char_slice().iter().copied() {
+        if c.is_ascii_alphanumeric() {
+            out.push(c);
+        } else if c == '_' {
+            out.push('_');
+            out.push('_');
+        } else {
+            out.push('_');
+            push_hex_digits_compact(&mut out, c as u32);
+            out.push('_');
+        }
+    }
+    out
+}
+
+/// Append the minimal-width uppercase hex representation of `value` (at least one digit).
+fn push_hex_digits_compact(out: &mut WString, value: u32) {
+    let mut digits = [0u8; 8];
+    let mut len = 0;
+    let mut v = value;
+    loop {
+        digits[len] = char::from_digit(v & 0xF, 16).unwrap().to_ascii_uppercase() as u8;
+        len += 1;
+        v >>= 4;
+        if v == 0 {
+            break;
+        }
+    }
+    for &d in digits[..len].iter().rev() {
+        out.push(d as char);
+    }
+}
+
+/// Escape PCRE2 metacharacters in `input` so it matches only literally when used as (part of) a
+/// regex.
+fn escape_string_regex(input: &wstr) -> WString {
+    const METACHARS: &[char] = &[
+        '.', '^', '$', '*', '+', '?', '(', ')', '[', ']', '{', '}', '|', '\\', '-',
+    ];
+    let mut out = WString::new();
+    for c in input.as_char_slice().iter().copied() {
+        if METACHARS.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// The single-letter suffix of a C-style backslash escape (`\n`, `\t`, ...) for `c`, as used by
+/// [`EscapeStringStyle::PosixDollar`], or `None` if `c` must fall back to an octal escape.
+fn posix_dollar_named_escape(c: char) -> Option<char> {
+    Some(match c {
+        '\x07' => 'a', // BEL
+        '\x08' => 'b', // backspace
+        '\x1b' => 'e', // ESC
+        '\x0c' => 'f', // form feed
+        '\n' => 'n',
+        '\r' => 'r',
+        '\t' => 't',
+        '\x0b' => 'v', // vertical tab
+        _ => return None,
+    })
+}
+
+/// Append the 3 octal digits of an `\ooo` escape for `value`, which must fit in a byte. The
+/// caller pushes the leading backslash.
+fn push_octal_escape(out: &mut WString, value: u32) {
+    for shift in (0..3).rev() {
+        let digit = (value >> (shift * 3)) & 0x7;
+        out.push(char::from_digit(digit, 8).unwrap());
+    }
+}
+
+/// Whether `c` needs `escape_string_posix_dollar` to quote the whole string for POSIX shells to
+/// consume it unambiguously. This is a superset of fish's own tokenizer-special set (per
+/// [`is_script_special`]) because it also has to account for characters other shells treat
+/// specially that fish doesn't, like backtick command substitution and `!` history expansion.
+fn posix_dollar_needs_quoting(c: char, index: usize) -> bool {
+    c.is_control()
+        || c == '\\'
+        || c == '\''
+        || c == '`'
+        || c == '!'
+        || is_script_special(c, index, false)
+}
+
+/// Emit `input` as an ANSI-C `$'...'` quoted string, per [`EscapeStringStyle::PosixDollar`].
+/// Leaves the string untouched if it contains nothing that needs quoting; the empty string is
+/// always quoted so it survives word splitting as a distinct argument.
+fn escape_string_posix_dollar(input: &wstr) -> WString {
+    let chars = input.as_char_slice();
+    let needs_quoting = chars.is_empty()
+        || chars
+            .iter()
+            .enumerate()
+            .any(|(i, &c)| posix_dollar_needs_quoting(c, i));
+
+    let mut out = WString::new();
+    if !needs_quoting {
+        for &c in chars {
+            out.push(c);
+        }
+        return out;
+    }
+
+    out.push('$');
+    out.push('\'');
+    for &c in chars {
+        if c == '\\' || c == '\'' {
+            out.push('\\');
+            out.push(c);
+        } else if let Some(letter) = posix_dollar_named_escape(c) {
+            out.push('\\');
+            out.push(letter);
+        } else if c.is_control() {
+            out.push('\\');
+            push_octal_escape(&mut out, c as u32);
+        } else {
+            out.push(c);
+        }
+    }
+    out.push('\'');
+    out
+}
+
+/// The reason [`unescape_string`] rejected its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnescapeErrorKind {
+    /// A `'` or `"` was opened but never closed.
+    UnterminatedQuote,
+    /// The input ended with a lone `\` that had nothing left to escape.
+    TrailingBackslash,
+    /// A `\x`/`\u`/`\U` or `Url`-style `%HH` escape contained a character that isn't a hex
+    /// digit, or a `Var`-style `_HEX_` escape had no digits or a non-hex one. Also used for a
+    /// `PosixDollar` `\` followed by neither a named escape nor an octal digit.
+    InvalidHexEscape,
+    /// A `\x`/`\u`/`\U` or `Url`-style `%HH` escape ran out of input before its digit count was
+    /// satisfied, or a `Var`-style `_HEX_` escape has no closing `_`.
+    TooShortCodeEscape,
+    /// A `\u`/`\U` escape, or a `Var`-style `_HEX_` escape, named a value that is not a valid
+    /// Unicode scalar value (e.g. a surrogate, or a value above `U+10FFFF`), or `Url`-style
+    /// percent-decoding produced bytes that aren't valid UTF-8.
+    InvalidCodepoint,
+}
+
+/// An error produced by [`unescape_string`], naming the problem and where in `s` it occurred.
+///
+/// `range` indexes into `s` in [`wstr`] (i.e. `wchar_t`) units, not UTF-8 bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnescapeError {
+    pub range: std::ops::Range<usize>,
+    pub kind: UnescapeErrorKind,
+}
+
+/// Parse the output of [`escape_string`] back into the string it was escaped from. The inverse of
+/// [`escape_string`].
+pub fn unescape_string(s: &wstr, style: EscapeStringStyle<'_>) -> Result<WString, UnescapeError> {
+    match style {
+        EscapeStringStyle::Script(_) => unescape_string_script(s),
+        EscapeStringStyle::Url => unescape_string_url(s),
+        EscapeStringStyle::Var => unescape_string_var(s),
+        EscapeStringStyle::Regex => unescape_string_regex(s),
+        EscapeStringStyle::PosixDollar => unescape_string_posix_dollar(s),
+    }
+}
+
+/// Quoting state while unescaping [`EscapeStringStyle::Script`] input.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ScriptQuote {
+    None,
+    Single,
+    Double,
+}
+
+fn unescape_string_script(input: &wstr) -> Result<WString, UnescapeError> {
+    let chars = input.as_char_slice();
+    let mut out = WString::new();
+    let mut state = ScriptQuote::None;
+    let mut quote_start = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match state {
+            ScriptQuote::None => match c {
+                '\'' => {
+                    state = ScriptQuote::Single;
+                    quote_start = i;
+                    i += 1;
+                }
+                '"' => {
+                    state = ScriptQuote::Double;
+                    quote_start = i;
+                    i += 1;
+                }
+                '\\' => {
+                    let (emitted, consumed) = unescape_backslash(chars, i)?;
+                    out.push(emitted);
+                    i += consumed;
+                }
+                _ => {
+                    out.push(c);
+                    i += 1;
+                }
+            },
+            // Inside single quotes only `\\` and `\'` are special; every other backslash is
+            // literal, matching fish's actual quoting rules.
+            ScriptQuote::Single => match c {
+                '\'' => {
+                    state = ScriptQuote::None;
+                    i += 1;
+                }
+                '\\' if matches!(chars.get(i + 1), Some('\\') | Some('\'')) => {
+                    out.push(chars[i + 1]);
+                    i += 2;
+                }
+                _ => {
+                    out.push(c);
+                    i += 1;
+                }
+            },
+            // Inside double quotes only `\\`, `\"` and `\$` are special; every other backslash is
+            // literal, matching fish's actual quoting rules.
+            ScriptQuote::Double => match c {
+                '"' => {
+                    state = ScriptQuote::None;
+                    i += 1;
+                }
+                '\\' if matches!(chars.get(i + 1), Some('\\') | Some('"') | Some('$')) => {
+                    out.push(chars[i + 1]);
+                    i += 2;
+                }
+                _ => {
+                    out.push(c);
+                    i += 1;
+                }
+            },
+        }
+    }
+
+    if state != ScriptQuote::None {
+        return Err(UnescapeError {
+            range: quote_start..chars.len(),
+            kind: UnescapeErrorKind::UnterminatedQuote,
+        });
+    }
+
+    Ok(out)
+}
+
+/// Decode the backslash escape starting at `chars[backslash_pos]`. Returns the character it
+/// decodes to and the number of input characters consumed (including the backslash itself).
+fn unescape_backslash(chars: &[char], backslash_pos: usize) -> Result<(char, usize), UnescapeError> {
+    let next_pos = backslash_pos + 1;
+    let next = chars.get(next_pos).copied().ok_or(UnescapeError {
+        range: backslash_pos..backslash_pos + 1,
+        kind: UnescapeErrorKind::TrailingBackslash,
+    })?;
+
+    let named = match next {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        'a' => Some('\x07'),
+        'b' => Some('\x08'),
+        'e' => Some('\x1b'),
+        'f' => Some('\x0c'),
+        'v' => Some('\x0b'),
+        _ => None,
+    };
+    if let Some(c) = named {
+        return Ok((c, 2));
+    }
+
+    match next {
+        'x' => {
+            let value = parse_fixed_hex(chars, next_pos + 1, 2, backslash_pos)?;
+            // Every byte value is a valid Unicode scalar value, so this can't fail.
+            Ok((char::from_u32(value).unwrap(), 2 + 2))
+        }
+        'u' => {
+            let value = parse_fixed_hex(chars, next_pos + 1, 4, backslash_pos)?;
+            let c = char::from_u32(value).ok_or(UnescapeError {
+                range: backslash_pos..next_pos + 1 + 4,
+                kind: UnescapeErrorKind::InvalidCodepoint,
+            })?;
+            Ok((c, 2 + 4))
+        }
+        'U' => {
+            let value = parse_fixed_hex(chars, next_pos + 1, 8, backslash_pos)?;
+            let c = char::from_u32(value).ok_or(UnescapeError {
+                range: backslash_pos..next_pos + 1 + 8,
+                kind: UnescapeErrorKind::InvalidCodepoint,
+            })?;
+            Ok((c, 2 + 8))
+        }
+        '0'..='7' => {
+            let (value, n) = parse_octal(chars, next_pos);
+            Ok((char::from_u32(value).unwrap(), 1 + n))
+        }
+        // A backslash in front of anything else just strips that character's special meaning.
+        other => Ok((other, 2)),
+    }
+}
+
+/// Parse exactly `digits` hex digits starting at `pos`, reporting errors relative to
+/// `escape_start` (the position of the introducing backslash).
+fn parse_fixed_hex(
+    chars: &[char],
+    pos: usize,
+    digits: usize,
+    escape_start: usize,
+) -> Result<u32, UnescapeError> {
+    let mut value: u32 = 0;
+    for n in 0..digits {
+        match chars.get(pos + n) {
+            Some(&c) if c.is_ascii_hexdigit() => value = value * 16 + c.to_digit(16).unwrap(),
+            Some(_) => {
+                return Err(UnescapeError {
+                    range: escape_start..(pos + digits).min(chars.len()),
+                    kind: UnescapeErrorKind::InvalidHexEscape,
+                })
+            }
+            None => {
+                return Err(UnescapeError {
+                    range: escape_start..chars.len(),
+                    kind: UnescapeErrorKind::TooShortCodeEscape,
+                })
+            }
+        }
+    }
+    Ok(value)
+}
+
+/// Parse up to 3 octal digits starting at `pos`, returning the byte value (masked to `0..=0xFF`,
+/// matching [`escape_string`]'s octal escapes) and the number of digits consumed, which is 0 if
+/// `chars[pos]` isn't an octal digit.
+fn parse_octal(chars: &[char], pos: usize) -> (u32, usize) {
+    let mut value: u32 = 0;
+    let mut n = 0;
+    while n < 3 {
+        match chars.get(pos + n) {
+            Some(&c) if ('0'..='7').contains(&c) => {
+                value = value * 8 + c.to_digit(8).unwrap();
+                n += 1;
+            }
+            _ => break,
+        }
+    }
+    (value & 0xFF, n)
+}
+
+/// Decode a percent-encoded URL string back to the original text.
+fn unescape_string_url(input: &wstr) -> Result<WString, UnescapeError> {
+    let chars = input.as_char_slice();
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '%' {
+            let value = parse_fixed_hex(chars, i + 1, 2, i)?;
+            bytes.push(value as u8);
+            i += 3;
+        } else {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            i += 1;
+        }
+    }
+    let decoded = String::from_utf8(bytes).map_err(|_| UnescapeError {
+        range: 0..chars.len(),
+        kind: UnescapeErrorKind::InvalidCodepoint,
+    })?;
+    let mut out = WString::new();
+    for c in decoded.chars() {
+        out.push(c);
+    }
+    Ok(out)
+}
+
+/// Decode the `_HEX_`/`__` variable-name encoding back to the original text.
+fn unescape_string_var(input: &wstr) -> Result<WString, UnescapeError> {
+    let chars = input.as_char_slice();
+    let mut out = WString::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c != '_' {
+            out.push(c);
+            i += 1;
+            continue;
+        }
+
+        if chars.get(i + 1) == Some(&'_') {
+            out.push('_');
+            i += 2;
+            continue;
+        }
+
+        let digits_start = i + 1;
+        let digits_end = chars[digits_start..]
+            .iter()
+            .position(|&c| c == '_')
+            .map(|p| digits_start + p)
+            .ok_or(UnescapeError {
+                range: i..chars.len(),
+                kind: UnescapeErrorKind::TooShortCodeEscape,
+            })?;
+
+        let digits = &chars[digits_start..digits_end];
+        if digits.is_empty() || !digits.iter().all(|c| c.is_ascii_hexdigit()) {
+            return Err(UnescapeError {
+                range: i..digits_end + 1,
+                kind: UnescapeErrorKind::InvalidHexEscape,
+            });
+        }
+
+        let mut value: u32 = 0;
+        for &d in digits {
+            value = value * 16 + d.to_digit(16).unwrap();
+        }
+        let decoded = char::from_u32(value).ok_or(UnescapeError {
+            range: i..digits_end + 1,
+            kind: UnescapeErrorKind::InvalidCodepoint,
+        })?;
+        out.push(decoded);
+        i = digits_end + 1;
+    }
+    Ok(out)
+}
+
+/// Undo backslash-escaping of PCRE2 metacharacters.
+fn unescape_string_regex(input: &wstr) -> Result<WString, UnescapeError> {
+    let chars = input.as_char_slice();
+    let mut out = WString::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c != '\\' {
+            out.push(c);
+            i += 1;
+            continue;
+        }
+        match chars.get(i + 1) {
+            Some(&next) => {
+                out.push(next);
+                i += 2;
+            }
+            None => {
+                return Err(UnescapeError {
+                    range: i..i + 1,
+                    kind: UnescapeErrorKind::TrailingBackslash,
+                })
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Decode an [`EscapeStringStyle::PosixDollar`] string. If it isn't wrapped in `$'...'` it's
+/// taken as already-literal, mirroring how [`escape_string_posix_dollar`] leaves such strings
+/// untouched.
+fn unescape_string_posix_dollar(input: &wstr) -> Result<WString, UnescapeError> {
+    let chars = input.as_char_slice();
+    let is_quoted =
+        chars.len() >= 3 && chars[0] == '$' && chars[1] == '\'' && chars[chars.len() - 1] == '\'';
+    if !is_quoted {
+        let mut out = WString::new();
+        for &c in chars {
+            out.push(c);
+        }
+        return Ok(out);
+    }
+
+    let body = &chars[2..chars.len() - 1];
+    let mut out = WString::new();
+    let mut i = 0;
+    while i < body.len() {
+        let c = body[i];
+        if c != '\\' {
+            out.push(c);
+            i += 1;
+            continue;
+        }
+
+        let next = *body.get(i + 1).ok_or(UnescapeError {
+            range: (2 + i)..(2 + i + 1),
+            kind: UnescapeErrorKind::TrailingBackslash,
+        })?;
+
+        let named = match next {
+            'a' => Some('\x07'),
+            'b' => Some('\x08'),
+            'e' => Some('\x1b'),
+            'f' => Some('\x0c'),
+            'n' => Some('\n'),
+            'r' => Some('\r'),
+            't' => Some('\t'),
+            'v' => Some('\x0b'),
+            '\\' => Some('\\'),
+            '\'' => Some('\''),
+            _ => None,
+        };
+        if let Some(decoded) = named {
+            out.push(decoded);
+            i += 2;
+            continue;
+        }
+
+        let (value, n) = parse_octal(body, i + 1);
+        if n == 0 {
+            return Err(UnescapeError {
+                range: (2 + i)..(2 + i + 2),
+                kind: UnescapeErrorKind::InvalidHexEscape,
+            });
+        }
+        out.push(char::from_u32(value).unwrap());
+        i += 1 + n;
+    }
+    Ok(out)
+}
+
+/// The reason [`validate_escapes`] flagged part of its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeErrorKind {
+    /// A trailing lone `\` with nothing left to escape.
+    EmptyEscape,
+    /// A `\` followed by a letter that isn't a recognized escape and isn't a `\x`/`\u`/`\U`
+    /// introducer. In `PosixDollar` style, a `\` followed by any character that is neither one
+    /// of its named escapes nor an octal digit (`\x` included).
+    InvalidAsciiEscape,
+    /// A `\x`/`\u`/`\U` or `Url`-style `%HH` escape doesn't have enough hex digits, or a
+    /// `Var`-style `_HEX_` escape is unclosed, empty, or has a non-hex digit.
+    TooShortHexEscape,
+    /// A `\u`/`\U` escape, or a `Var`-style `_HEX_` escape, names a value that is not a valid
+    /// Unicode scalar value.
+    InvalidCodepoint,
+    /// A `'` or `"` was opened but never closed.
+    UnterminatedQuote,
+}
+
+/// A single problem found by [`validate_escapes`], naming the issue and where in `s` it occurred.
+///
+/// `range` indexes into `s` in [`wstr`] (i.e. `wchar_t`) units, not UTF-8 bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscapeError {
+    pub range: std::ops::Range<usize>,
+    pub kind: EscapeErrorKind,
+}
+
+/// Scan `s` for malformed escape sequences, collecting every problem found rather than stopping
+/// at the first one. Unlike [`unescape_string`] this doesn't produce an unescaped result - it's
+/// meant for syntax highlighting and linting, where every diagnostic (with its span) is wanted at
+/// once rather than just the first.
+pub fn validate_escapes(s: &wstr, style: EscapeStringStyle<'_>) -> Vec<EscapeError> {
+    match style {
+        EscapeStringStyle::Script(_) => validate_escapes_script(s),
+        EscapeStringStyle::Url => validate_escapes_url(s),
+        EscapeStringStyle::Var => validate_escapes_var(s),
+        EscapeStringStyle::Regex => validate_escapes_regex(s),
+        EscapeStringStyle::PosixDollar => validate_escapes_posix_dollar(s),
+    }
+}
+
+fn validate_escapes_script(input: &wstr) -> Vec<EscapeError> {
+    let chars = input.as_char_slice();
+    let mut errors = Vec::new();
+    let mut state = ScriptQuote::None;
+    let mut quote_start = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match state {
+            ScriptQuote::None => match c {
+                '\'' => {
+                    state = ScriptQuote::Single;
+                    quote_start = i;
+                    i += 1;
+                }
+                '"' => {
+                    state = ScriptQuote::Double;
+                    quote_start = i;
+                    i += 1;
+                }
+                '\\' => i += validate_backslash(chars, i, &mut errors),
+                _ => i += 1,
+            },
+            ScriptQuote::Single => match c {
+                '\'' => {
+                    state = ScriptQuote::None;
+                    i += 1;
+                }
+                '\\' if matches!(chars.get(i + 1), Some('\\') | Some('\'')) => i += 2,
+                _ => i += 1,
+            },
+            ScriptQuote::Double => match c {
+                '"' => {
+                    state = ScriptQuote::None;
+                    i += 1;
+                }
+                '\\' if matches!(chars.get(i + 1), Some('\\') | Some('"') | Some('$')) => i += 2,
+                _ => i += 1,
+            },
+        }
+    }
+
+    if state != ScriptQuote::None {
+        errors.push(EscapeError {
+            range: quote_start..chars.len(),
+            kind: EscapeErrorKind::UnterminatedQuote,
+        });
+    }
+
+    errors
+}
+
+/// Validate the backslash escape starting at `chars[backslash_pos]`, appending to `errors` if
+/// it's malformed. Returns the number of characters to advance by, always at least 1, so the
+/// caller can keep scanning after a bad escape instead of bailing out.
+fn validate_backslash(chars: &[char], backslash_pos: usize, errors: &mut Vec<EscapeError>) -> usize {
+    let next_pos = backslash_pos + 1;
+    let Some(&next) = chars.get(next_pos) else {
+        errors.push(EscapeError {
+            range: backslash_pos..backslash_pos + 1,
+            kind: EscapeErrorKind::EmptyEscape,
+        });
+        return 1;
+    };
+
+    if matches!(next, 'n' | 't' | 'r' | 'a' | 'b' | 'e' | 'f' | 'v') {
+        return 2;
+    }
+
+    match next {
+        'x' => validate_hex_escape(chars, next_pos + 1, 2, backslash_pos, errors),
+        'u' => validate_hex_escape(chars, next_pos + 1, 4, backslash_pos, errors),
+        'U' => validate_hex_escape(chars, next_pos + 1, 8, backslash_pos, errors),
+        '0'..='7' => {
+            let (_, n) = parse_octal(chars, next_pos);
+            1 + n
+        }
+        // Any other punctuation just escapes that character literally - only an unrecognized
+        // *letter* escape is treated as a mistake.
+        c if c.is_ascii_alphabetic() => {
+            errors.push(EscapeError {
+                range: backslash_pos..next_pos + 1,
+                kind: EscapeErrorKind::InvalidAsciiEscape,
+            });
+            2
+        }
+        _ => 2,
+    }
+}
+
+/// Validate a `\x`/`\u`/`\U` escape's `digits` hex digits starting at `pos`, appending to `errors`
+/// if short or (for `\u`/`\U`) the resulting codepoint isn't valid. Returns the number of
+/// characters the whole escape (including the introducing `\` and letter) occupies.
+fn validate_hex_escape(
+    chars: &[char],
+    pos: usize,
+    digits: usize,
+    backslash_pos: usize,
+    errors: &mut Vec<EscapeError>,
+) -> usize {
+    let mut n = 0;
+    while n < digits {
+        match chars.get(pos + n) {
+            Some(&c) if c.is_ascii_hexdigit() => n += 1,
+            _ => break,
+        }
+    }
+
+    if n < digits {
+        errors.push(EscapeError {
+            range: backslash_pos..(pos + n).min(chars.len()),
+            kind: EscapeErrorKind::TooShortHexEscape,
+        });
+        return 2 + n;
+    }
+
+    if digits > 2 {
+        let mut value: u32 = 0;
+        for k in 0..digits {
+            value = value * 16 + chars[pos + k].to_digit(16).unwrap();
+        }
+        if char::from_u32(value).is_none() {
+            errors.push(EscapeError {
+                range: backslash_pos..pos + digits,
+                kind: EscapeErrorKind::InvalidCodepoint,
+            });
+        }
+    }
+
+    2 + digits
+}
+
+fn validate_escapes_url(input: &wstr) -> Vec<EscapeError> {
+    let chars = input.as_char_slice();
+    let mut errors = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '%' {
+            i += 1;
+            continue;
+        }
+        let mut n = 0;
+        while n < 2 {
+            match chars.get(i + 1 + n) {
+                Some(&c) if c.is_ascii_hexdigit() => n += 1,
+                _ => break,
+            }
+        }
+        if n < 2 {
+            errors.push(EscapeError {
+                range: i..(i + 1 + n).min(chars.len()),
+                kind: EscapeErrorKind::TooShortHexEscape,
+            });
+        }
+        i += 1 + n;
+    }
+    errors
+}
+
+fn validate_escapes_var(input: &wstr) -> Vec<EscapeError> {
+    let chars = input.as_char_slice();
+    let mut errors = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '_' {
+            i += 1;
+            continue;
+        }
+        if chars.get(i + 1) == Some(&'_') {
+            i += 2;
+            continue;
+        }
+
+        let digits_start = i + 1;
+        let Some(digits_end) = chars[digits_start..]
+            .iter()
+            .position(|&c| c == '_')
+            .map(|p| digits_start + p)
+        else {
+            errors.push(EscapeError {
+                range: i..chars.len(),
+                kind: EscapeErrorKind::TooShortHexEscape,
+            });
+            break;
+        };
+
+        let digits = &chars[digits_start..digits_end];
+        if digits.is_empty() || !digits.iter().all(|c| c.is_ascii_hexdigit()) {
+            errors.push(EscapeError {
+                range: i..digits_end + 1,
+                kind: EscapeErrorKind::TooShortHexEscape,
+            });
+        } else {
+            let mut value: u32 = 0;
+            for &d in digits {
+                value = value * 16 + d.to_digit(16).unwrap();
+            }
+            if char::from_u32(value).is_none() {
+                errors.push(EscapeError {
+                    range: i..digits_end + 1,
+                    kind: EscapeErrorKind::InvalidCodepoint,
+                });
+            }
+        }
+        i = digits_end + 1;
+    }
+    errors
+}
+
+fn validate_escapes_regex(input: &wstr) -> Vec<EscapeError> {
+    let chars = input.as_char_slice();
+    let mut errors = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '\\' {
+            i += 1;
+            continue;
+        }
+        if chars.get(i + 1).is_none() {
+            errors.push(EscapeError {
+                range: i..i + 1,
+                kind: EscapeErrorKind::EmptyEscape,
+            });
+            i += 1;
+        } else {
+            i += 2;
+        }
+    }
+    errors
+}
+
+fn validate_escapes_posix_dollar(input: &wstr) -> Vec<EscapeError> {
+    let chars = input.as_char_slice();
+    let mut errors = Vec::new();
+
+    if !(chars.len() >= 2 && chars[0] == '$' && chars[1] == '\'') {
+        // Not wrapped in $'...' at all - escape_string_posix_dollar only quotes when needed, so
+        // there's nothing to validate.
+        return errors;
+    }
+    if chars.len() < 3 || chars[chars.len() - 1] != '\'' {
+        errors.push(EscapeError {
+            range: 0..chars.len(),
+            kind: EscapeErrorKind::UnterminatedQuote,
+        });
+        return errors;
+    }
+
+    let body = &chars[2..chars.len() - 1];
+    let mut i = 0;
+    while i < body.len() {
+        if body[i] != '\\' {
+            i += 1;
+            continue;
+        }
+        match body.get(i + 1) {
+            None => {
+                errors.push(EscapeError {
+                    range: (2 + i)..(2 + i + 1),
+                    kind: EscapeErrorKind::EmptyEscape,
+                });
+                i += 1;
+            }
+            Some('a' | 'b' | 'e' | 'f' | 'n' | 'r' | 't' | 'v' | '\\' | '\'') => i += 2,
+            Some(&next) if ('0'..='7').contains(&next) => {
+                let (_, n) = parse_octal(body, i + 1);
+                i += 1 + n;
+            }
+            Some(_) => {
+                errors.push(EscapeError {
+                    range: (2 + i)..(2 + i + 2),
+                    kind: EscapeErrorKind::InvalidAsciiEscape,
+                });
+                i += 2;
+            }
+        }
+    }
+    errors
+}
+
 /// Test if the string is a valid function name.
 pub fn valid_func_name(name: &wstr) -> bool {
     if name.is_empty() {
@@ -189,7 +1251,10 @@ macro_rules! assert_sorted_by_name {
 }
 mod tests {
     use crate::{
-        common::{escape_string, EscapeStringStyle},
+        common::{
+            escape_string, unescape_string, validate_escapes, EscapeErrorKind, EscapeFlags,
+            EscapeStringStyle, UnescapeErrorKind,
+        },
         wchar::widestrs,
     };
 
@@ -213,7 +1278,169 @@ mod tests {
             regex("not really escaped\\?"L),
             "not really escaped\\\\\\?"L
         );
+
+        let script = |input| escape_string(input, EscapeStringStyle::Script(EscapeFlags::default()));
+
+        // plain text should not be needlessly escaped
+        assert_eq!(script("abc"L), "abc"L);
+
+        // shell-special characters get a backslash
+        assert_eq!(script("a;b"L), "a\\;b"L);
+        assert_eq!(script("hello world"L), "hello\\ world"L);
+
+        // control characters get their short name
+        assert_eq!(script("a\nb"L), "a\\nb"L);
+
+        // the empty string becomes a pair of quotes, unless quoting is disabled
+        assert_eq!(script(""L), "''"L);
+        assert_eq!(
+            escape_string(
+                ""L,
+                EscapeStringStyle::Script(EscapeFlags {
+                    no_quoted: true,
+                    ..Default::default()
+                })
+            ),
+            ""L
+        );
+
+        // a caller can force additional characters to be escaped for its own context
+        assert_eq!(
+            escape_string(
+                "user:pass"L,
+                EscapeStringStyle::Script(EscapeFlags {
+                    additional_escaped_chars: &[':'],
+                    ..Default::default()
+                })
+            ),
+            "user\\:pass"L
+        );
+
+        // forced escapes still apply even when the single-quoted form would otherwise be
+        // shorter - the quoted-wrap optimization can't express a forced escape, so it's skipped
+        assert_eq!(
+            escape_string(
+                "a:b:c:d"L,
+                EscapeStringStyle::Script(EscapeFlags {
+                    additional_escaped_chars: &[':'],
+                    ..Default::default()
+                })
+            ),
+            "a\\:b\\:c\\:d"L
+        );
+
+        let url = |input| escape_string(input, EscapeStringStyle::Url);
+        assert_eq!(url("abc123"L), "abc123"L);
+        assert_eq!(url("a b"L), "a%20b"L);
+
+        let var = |input| escape_string(input, EscapeStringStyle::Var);
+        assert_eq!(var("abc123"L), "abc123"L);
+        assert_eq!(var("a_b"L), "a__b"L);
+        assert_eq!(var("a b"L), "a_20_b"L);
+    }
+
+    #[widestrs]
+    pub fn test_unescape_string() {
+        // escape_string() followed by unescape_string() should round-trip for every style.
+        for &input in &["hello world"L, ""L, "a\nb\tc"L, "semi;colon"L, "quote'd"L] {
+            for style in [
+                EscapeStringStyle::Script(EscapeFlags::default()),
+                EscapeStringStyle::Url,
+                EscapeStringStyle::Var,
+                EscapeStringStyle::Regex,
+                EscapeStringStyle::PosixDollar,
+            ] {
+                let escaped = escape_string(input, style);
+                assert_eq!(unescape_string(&escaped, style).as_deref(), Ok(input));
+            }
+        }
+
+        let script = |input| unescape_string(input, EscapeStringStyle::Script(EscapeFlags::default()));
+
+        assert_eq!(script("\\x41"L).as_deref(), Ok("A"L));
+        assert_eq!(script("'abc"L).unwrap_err().kind, UnescapeErrorKind::UnterminatedQuote);
+        assert_eq!(script("abc\\"L).unwrap_err().kind, UnescapeErrorKind::TrailingBackslash);
+        assert_eq!(script("\\xgg"L).unwrap_err().kind, UnescapeErrorKind::InvalidHexEscape);
+        assert_eq!(script("\\x4"L).unwrap_err().kind, UnescapeErrorKind::TooShortCodeEscape);
+        assert_eq!(
+            script("\\uD800"L).unwrap_err().kind,
+            UnescapeErrorKind::InvalidCodepoint
+        );
+
+        // inside double quotes only \\, \" and \$ are special - other backslashes are literal
+        assert_eq!(script("\"C:\\temp\""L).as_deref(), Ok("C:\\temp"L));
+        assert_eq!(script("\"a\\$b\""L).as_deref(), Ok("a$b"L));
+        assert_eq!(script("\"a\\\"b\""L).as_deref(), Ok("a\"b"L));
+    }
+
+    #[widestrs]
+    pub fn test_escape_string_posix_dollar() {
+        let posix = |input| escape_string(input, EscapeStringStyle::PosixDollar);
+
+        // nothing to quote - left untouched
+        assert_eq!(posix("hello"L), "hello"L);
+
+        // whitespace and shell metacharacters force quoting, even with nothing else to escape
+        assert_eq!(posix("hello world"L), "$'hello world'"L);
+        assert_eq!(posix("semi;colon"L), "$'semi;colon'"L);
+
+        // control chars and the characters $'...' itself needs escaped force quoting
+        assert_eq!(posix("a\nb"L), "$'a\\nb'"L);
+        assert_eq!(posix("a\\b"L), "$'a\\\\b'"L);
+        assert_eq!(posix("a'b"L), "$'a\\'b'"L);
+
+        // backtick and `!` aren't special to fish, but other POSIX shells treat them as command
+        // substitution and history expansion respectively - force quoting for them too
+        assert_eq!(posix("`id`"L), "$'`id`'"L);
+        assert_eq!(posix("oh!"L), "$'oh!'"L);
+
+        // the empty string must still be quoted, or it vanishes under word splitting
+        assert_eq!(posix(""L), "$''"L);
+    }
+
+    #[widestrs]
+    pub fn test_validate_escapes() {
+        let script = |input| validate_escapes(input, EscapeStringStyle::Script(EscapeFlags::default()));
+
+        // well-formed input has no errors
+        assert_eq!(script("hello \\n world"L), vec![]);
+
+        // every problem is reported, not just the first
+        let errors = script("\\q and \\z"L);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, EscapeErrorKind::InvalidAsciiEscape);
+        assert_eq!(errors[0].range, 0..2);
+        assert_eq!(errors[1].kind, EscapeErrorKind::InvalidAsciiEscape);
+        assert_eq!(errors[1].range, 7..9);
+
+        assert_eq!(
+            script("abc\\"L)[0].kind,
+            EscapeErrorKind::EmptyEscape
+        );
+        assert_eq!(
+            script("\\x4"L)[0].kind,
+            EscapeErrorKind::TooShortHexEscape
+        );
+        assert_eq!(
+            script("\\uD800"L)[0].kind,
+            EscapeErrorKind::InvalidCodepoint
+        );
+        assert_eq!(
+            script("'unterminated"L)[0].kind,
+            EscapeErrorKind::UnterminatedQuote
+        );
+
+        // inside double quotes only \\, \" and \$ are special - other backslashes are literal
+        // and must not be reported as invalid escapes
+        assert_eq!(script("\"\\q\""L), vec![]);
+        assert_eq!(script("\"C:\\temp\\file\""L), vec![]);
     }
 }
 
-crate::ffi_tests::add_test!("escape_string", tests::test_escape_string);
\ No newline at end of file
+crate::ffi_tests::add_test!("escape_string", tests::test_escape_string);
+crate::ffi_tests::add_test!("unescape_string", tests::test_unescape_string);
+crate::ffi_tests::add_test!(
+    "escape_string_posix_dollar",
+    tests::test_escape_string_posix_dollar
+);
+crate::ffi_tests::add_test!("validate_escapes", tests::test_validate_escapes);
\ No newline at end of file